@@ -0,0 +1,169 @@
+//! A backend which resolves credentials from AWS Secrets Manager.
+
+use backend::{Backend, BoxedError};
+use errors::Error;
+use rusoto::{DefaultCredentialsProvider, Region};
+use rusoto::secretsmanager::{GetSecretValueRequest, SecretsManagerClient};
+use rustc_serialize::json;
+use secretfile::{Location, Secretfile};
+use std::collections::BTreeMap;
+use std::env;
+use std::str::FromStr;
+
+/// A secret we've fetched from Secrets Manager, decoded as a JSON object
+/// of key-value pairs.
+type Secret = BTreeMap<String, String>;
+
+/// The part of the Secrets Manager API we actually use, split out as a
+/// trait so tests can substitute a fake instead of making real AWS
+/// calls.
+trait SecretSource {
+    /// Fetch the raw `SecretString` for the secret named `id`.
+    fn secret_string(&self, id: &str) -> Result<String, BoxedError>;
+}
+
+impl SecretSource for SecretsManagerClient<DefaultCredentialsProvider> {
+    fn secret_string(&self, id: &str) -> Result<String, BoxedError> {
+        let req = GetSecretValueRequest {
+            secret_id: id.to_owned(),
+            ..Default::default()
+        };
+        let resp = try!(self.get_secret_value(&req));
+        resp.secret_string.ok_or_else(|| {
+            From::from(format!("no SecretString for {}", id))
+        })
+    }
+}
+
+/// A backend backed by AWS Secrets Manager.
+pub struct Client {
+    /// Wherever we actually fetch secrets from.
+    client: Box<SecretSource>,
+    /// Mapping from environment-variable-style names to locations in
+    /// Secrets Manager.
+    secretfile: Secretfile,
+    /// Local cache of secrets, keyed by secret id.  We cache the whole
+    /// secret, not individual keys, to keep multi-field secrets (like a
+    /// username/password pair) consistent with each other.
+    secrets: BTreeMap<String, Secret>,
+}
+
+impl Client {
+    /// Create a client from an arbitrary secret source.  Used by
+    /// `default` and by tests, which substitute a fake `SecretSource`.
+    fn new(client: Box<SecretSource>, secretfile: Secretfile) -> Client {
+        Client {
+            client: client,
+            secretfile: secretfile,
+            secrets: BTreeMap::new(),
+        }
+    }
+
+    /// Build a client using the standard AWS environment: `AWS_REGION`
+    /// for the region, and the default AWS credential chain for
+    /// authentication.
+    pub fn default() -> Result<Client, Error> {
+        let region = try!(Client::region_from_env());
+        let secretfile = try!(Secretfile::default());
+        let credentials = try!(DefaultCredentialsProvider::new());
+        let client = SecretsManagerClient::new(credentials, region);
+        Ok(Client::new(Box::new(client), secretfile))
+    }
+
+    /// Look up the region we should use from `AWS_REGION`.
+    fn region_from_env() -> Result<Region, BoxedError> {
+        let name = try!(env::var("AWS_REGION").map_err(|_| {
+            From::<String>::from("missing AWS_REGION".to_owned())
+        }));
+        Region::from_str(&name).map_err(|_| {
+            From::from(format!("unknown AWS region {}", name))
+        })
+    }
+
+    /// Can we find enough configuration in our environment to build a
+    /// working Secrets Manager client?
+    pub fn is_enabled() -> bool {
+        Client::region_from_env().is_ok()
+    }
+
+    fn get_secret(&self, id: &str) -> Result<Secret, BoxedError> {
+        let secret_string = try!(self.client.secret_string(id));
+        Ok(try!(json::decode(&secret_string)))
+    }
+}
+
+impl Backend for Client {
+    fn get(&mut self, credential: &str) -> Result<String, BoxedError> {
+        match self.secretfile.get(credential) {
+            None => {
+                let msg = format!("No Secretfile entry for {}", credential);
+                Err(From::from(msg))
+            }
+            Some(&Location::Keyed(ref id, ref key)) => {
+                // As with vault, fetch and cache the whole secret, so
+                // that related keys (username/password, etc.) always
+                // come from the same version of the secret.
+                if !self.secrets.contains_key(id) {
+                    let secret = try!(self.get_secret(id));
+                    self.secrets.insert(id.to_owned(), secret);
+                }
+
+                let secret = self.secrets.get(id).unwrap();
+                secret.get(key).ok_or_else(|| {
+                    From::from(format!("No key {} in secret {}", key, id))
+                }).map(|v| v.clone())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use backend::{Backend, BoxedError};
+    use secretfile::Secretfile;
+    use std::collections::BTreeMap;
+    use super::{Client, SecretSource};
+
+    struct FakeSecretSource {
+        secrets: BTreeMap<String, String>,
+    }
+
+    impl SecretSource for FakeSecretSource {
+        fn secret_string(&self, id: &str) -> Result<String, BoxedError> {
+            self.secrets.get(id).cloned().ok_or_else(|| {
+                From::from(format!("no such secret: {}", id))
+            })
+        }
+    }
+
+    fn test_client() -> Client {
+        let mut secrets = BTreeMap::new();
+        secrets.insert("prod/db".to_owned(),
+                        "{\"username\": \"admin\", \"password\": \"hunter2\"}".to_owned());
+        let secretfile = Secretfile::from_str(
+            "DB_USERNAME prod/db:username\n\
+             DB_PASSWORD prod/db:password").unwrap();
+        Client::new(Box::new(FakeSecretSource { secrets: secrets }), secretfile)
+    }
+
+    #[test]
+    fn test_get_secret() {
+        let client = test_client();
+        let secret = client.get_secret("prod/db").unwrap();
+        assert_eq!("admin", secret.get("username").unwrap());
+    }
+
+    #[test]
+    fn test_get_caches_whole_secret() {
+        let mut client = test_client();
+        assert_eq!("admin", client.get("DB_USERNAME").unwrap());
+        assert_eq!("hunter2", client.get("DB_PASSWORD").unwrap());
+        assert!(client.secrets.contains_key("prod/db"));
+    }
+
+    #[test]
+    fn test_get_no_secretfile_entry() {
+        let mut client = test_client();
+        assert!(client.get("NO_SUCH_CREDENTIAL").is_err());
+    }
+}
@@ -1,8 +1,11 @@
 //! Backend which tries multiple other backends, in sequence.
 
+#[cfg(feature = "aws")]
+use aws;
 use backend::Backend;
 use envvar;
 use errors::{BoxedError, err, Error};
+use netrc;
 use secretfile::Secretfile;
 use vault;
 
@@ -13,16 +16,38 @@ pub struct Client {
 }
 
 impl Client {
-    /// Create a new environment variable client.
-    fn new() -> Client {
+    /// Create a new, empty chaining client with no backends configured.
+    /// Most callers will want `default` or `with_backends` instead.
+    pub fn new() -> Client {
         Client { backends: vec!() }
     }
 
+    /// Create a chaining client from an explicit, ordered list of
+    /// backends, for callers who want to build their own chain instead
+    /// of using `default`.
+    pub fn with_backends(backends: Vec<Box<Backend>>) -> Client {
+        Client { backends: backends }
+    }
+
     /// Add a new backend to our list, after the existing ones.
-    fn add<B: Backend + 'static>(&mut self, backend: B) {
+    pub fn add<B: Backend + 'static>(&mut self, backend: B) {
         self.backends.push(Box::new(backend));
     }
 
+    /// Like `add`, but for a backend that's already boxed (e.g. one
+    /// built behind a feature flag, where the concrete type may not be
+    /// available to the caller).
+    fn add_boxed(&mut self, backend: Box<Backend>) {
+        self.backends.push(backend);
+    }
+
+    /// Add a new backend to the front of our list, so it's tried before
+    /// any backend already configured.  Useful for giving a custom,
+    /// high-priority backend precedence over the standard chain.
+    pub fn insert_front<B: Backend + 'static>(&mut self, backend: B) {
+        self.backends.insert(0, Box::new(backend));
+    }
+
     /// Set up the standard chain, based on what appears to be available.
     pub fn default() -> Result<Client, Error> {
         let mut client = Client::new();
@@ -31,8 +56,34 @@ impl Client {
             debug!("Enabling vault backend");
             client.add(try!(vault::Client::default()));
         }
+        if let Ok(netrc_client) = netrc::Client::default() {
+            debug!("Enabling netrc backend");
+            client.add(netrc_client);
+        }
+        if let Some(aws_client) = try!(Client::maybe_aws_client()) {
+            debug!("Enabling AWS Secrets Manager backend");
+            client.add_boxed(aws_client);
+        }
         Ok(client)
     }
+
+    /// Build an AWS Secrets Manager backend if the `aws` feature is
+    /// compiled in and `AWS_REGION` is set in our environment.
+    #[cfg(feature = "aws")]
+    fn maybe_aws_client() -> Result<Option<Box<Backend>>, Error> {
+        if aws::Client::is_enabled() {
+            let client: Box<Backend> = Box::new(try!(aws::Client::default()));
+            Ok(Some(client))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Without the `aws` feature, there's no AWS backend to enable.
+    #[cfg(not(feature = "aws"))]
+    fn maybe_aws_client() -> Result<Option<Box<Backend>>, Error> {
+        Ok(None)
+    }
 }
 
 impl Backend for Client {
@@ -119,4 +170,55 @@ mod tests {
         assert_eq!("dummy2", client.file(&sf, "dummy.txt").unwrap());
         assert!(client.file(&sf, "nosuchfile.txt").is_err());
     }
+
+    /// A second dummy backend which answers `DUMMY` differently than
+    /// `DummyClient`, so we can tell which one of the two a chain
+    /// actually consulted.
+    struct OtherDummyClient;
+
+    impl OtherDummyClient {
+        pub fn default() -> Result<OtherDummyClient, Error> {
+            Ok(OtherDummyClient)
+        }
+    }
+
+    impl Backend for OtherDummyClient {
+        fn var(&mut self, _secretfile: &Secretfile, credential: &str) ->
+            Result<String, BoxedError>
+        {
+            if credential == "DUMMY" {
+                Ok("other-dummy".to_owned())
+            } else {
+                Err(err("Credential not supported"))
+            }
+        }
+
+        fn file(&mut self, _secretfile: &Secretfile, _path: &str) ->
+            Result<String, BoxedError>
+        {
+            Err(err("Credential not supported"))
+        }
+    }
+
+    #[test]
+    fn test_with_backends() {
+        let sf = Secretfile::from_str("").unwrap();
+        let backends: Vec<Box<Backend>> = vec![Box::new(DummyClient::default().unwrap())];
+        let mut client = Client::with_backends(backends);
+        assert_eq!("dummy", client.var(&sf, "DUMMY").unwrap());
+    }
+
+    #[test]
+    fn test_insert_front_takes_priority_over_added_backends() {
+        let sf = Secretfile::from_str("").unwrap();
+        let mut client = Client::new();
+        client.add(DummyClient::default().unwrap());
+        assert_eq!("dummy", client.var(&sf, "DUMMY").unwrap());
+
+        // A backend inserted at the front should win, even though
+        // `DummyClient` was already configured and can also answer
+        // `DUMMY`.
+        client.insert_front(OtherDummyClient::default().unwrap());
+        assert_eq!("other-dummy", client.var(&sf, "DUMMY").unwrap());
+    }
 }
@@ -0,0 +1,179 @@
+//! A backend which resolves credentials from `~/.netrc` (or the file
+//! named by `$NETRC`), as used by `curl`, `ftp` and many other tools.
+
+use backend::{Backend, BoxedError};
+use errors::Error;
+use secretfile::{Location, Secretfile};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+/// The fields we care about for a single `machine` (or `default`) entry.
+#[derive(Debug, Default, Clone)]
+struct Machine {
+    login: Option<String>,
+    password: Option<String>,
+}
+
+/// A backend which reads credentials out of a netrc file.
+struct Client {
+    /// The machines we parsed out of the netrc file, keyed by name.
+    /// The fallback entry, if any, is stored under `"default"`.
+    machines: BTreeMap<String, Machine>,
+    /// Mapping from environment-variable-style names to locations in
+    /// the netrc file.
+    secretfile: Secretfile,
+}
+
+impl Client {
+    /// Parse the contents of a netrc file.
+    fn parse(data: &str, secretfile: Secretfile) -> Client {
+        let mut machines: BTreeMap<String, Machine> = BTreeMap::new();
+        let mut current: Option<String> = None;
+
+        // `macdef` bodies run until the next blank *line*, which we'd
+        // lose track of if we tokenized on whitespace (which collapses
+        // blank lines away).  So we walk lines first, and only split
+        // each non-macro line into whitespace-separated tokens.
+        let mut lines = data.lines();
+        while let Some(line) = lines.next() {
+            let mut tokens = line.split_whitespace();
+            while let Some(token) = tokens.next() {
+                match token {
+                    "machine" => {
+                        if let Some(name) = tokens.next() {
+                            machines.entry(name.to_owned()).or_insert_with(Machine::default);
+                            current = Some(name.to_owned());
+                        }
+                    }
+                    "default" => {
+                        machines.entry("default".to_owned()).or_insert_with(Machine::default);
+                        current = Some("default".to_owned());
+                    }
+                    "login" | "password" => {
+                        let value = match tokens.next() {
+                            Some(value) => value.to_owned(),
+                            None => continue,
+                        };
+                        if let Some(ref name) = current {
+                            let machine = machines.entry(name.clone()).or_insert_with(Machine::default);
+                            if token == "login" {
+                                machine.login = Some(value);
+                            } else {
+                                machine.password = Some(value);
+                            }
+                        }
+                    }
+                    "macdef" => {
+                        // Consume the macro's name, then skip lines until
+                        // we hit a blank one, which ends the macro
+                        // definition.
+                        tokens.next();
+                        for line in &mut lines {
+                            if line.trim().is_empty() {
+                                break;
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Client { machines: machines, secretfile: secretfile }
+    }
+
+    /// Load and parse `~/.netrc`, or the file named by `$NETRC` if set.
+    pub fn default() -> Result<Client, Error> {
+        let path = match env::var("NETRC") {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => {
+                let home = try!(env::var("HOME").map_err(|_| {
+                    Error::from("missing HOME, needed to find ~/.netrc")
+                }));
+                PathBuf::from(home).join(".netrc")
+            }
+        };
+
+        let mut data = String::new();
+        let mut f = try!(File::open(&path));
+        try!(f.read_to_string(&mut data));
+        Ok(Client::parse(&data, try!(Secretfile::default())))
+    }
+
+    /// Look up a machine entry by name, falling back to the `default`
+    /// entry (if any).
+    fn machine(&self, name: &str) -> Option<&Machine> {
+        self.machines.get(name).or_else(|| self.machines.get("default"))
+    }
+}
+
+impl Backend for Client {
+    fn get(&mut self, credential: &str) -> Result<String, BoxedError> {
+        match self.secretfile.get(credential) {
+            None => {
+                let msg = format!("No Secretfile entry for {}", credential);
+                Err(From::from(msg))
+            }
+            Some(&Location::Keyed(ref machine, ref field)) => {
+                let machine = try!(self.machine(machine).ok_or_else(|| {
+                    From::from(format!("No netrc entry for machine {}", machine))
+                }));
+                let value = match &field[..] {
+                    "login" => &machine.login,
+                    "password" => &machine.password,
+                    _ => {
+                        let msg = format!("netrc fields are login or password, not {}", field);
+                        return Err(From::from(msg));
+                    }
+                };
+                value.clone().ok_or_else(|| {
+                    From::from(format!("No {} in netrc entry", field))
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use backend::Backend;
+    use secretfile::Secretfile;
+    use super::Client;
+
+    const NETRC: &'static str = "\
+machine example.com
+  login alice
+  password s3kr1t
+
+macdef uploadme
+  put a b
+
+default
+  login anonymous
+  password guest
+";
+
+    fn test_client() -> Client {
+        let secretfile = Secretfile::from_str(
+            "EXAMPLE_LOGIN example.com:login\n\
+             EXAMPLE_PASSWORD example.com:password\n\
+             OTHER_LOGIN other.example.com:login").unwrap();
+        Client::parse(NETRC, secretfile)
+    }
+
+    #[test]
+    fn test_get_known_machine() {
+        let mut client = test_client();
+        assert_eq!("alice", client.get("EXAMPLE_LOGIN").unwrap());
+        assert_eq!("s3kr1t", client.get("EXAMPLE_PASSWORD").unwrap());
+    }
+
+    #[test]
+    fn test_get_falls_back_to_default() {
+        let mut client = test_client();
+        assert_eq!("anonymous", client.get("OTHER_LOGIN").unwrap());
+    }
+}
@@ -1,11 +1,16 @@
 //! A very basic client for Hashicorp's Vault
 
 use backend::{Backend, BoxedError};
+use errors::Error;
 use hyper;
 use rustc_serialize::json;
 use secretfile::{Location, Secretfile};
 use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
 use std::io::Read;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
 // Define our custom vault token header for use with hyper.
 header! { (XVaultToken, "X-Vault-Token") => [String] }
@@ -17,10 +22,37 @@ header! { (XVaultToken, "X-Vault-Token") => [String] }
 struct Secret {
     /// The key-value pairs associated with this secret.
     data: BTreeMap<String, String>,
+    /// The ID of the lease on this secret, used to renew it.  Empty for
+    /// secrets which aren't leased.
+    lease_id: String,
     // How long this secret will remain valid for, in seconds.
     lease_duration: u64,
 }
 
+/// The body of a response to a lease renewal request.
+#[derive(Debug, RustcDecodable)]
+struct RenewedLease {
+    /// How long the renewed lease will remain valid for, in seconds.
+    lease_duration: u64,
+}
+
+/// The body of a response to a Vault login request, e.g. from the
+/// `approle` or `app-id` auth backends.
+#[derive(Debug, RustcDecodable)]
+struct AuthResponse {
+    /// The authentication details we care about.
+    auth: Auth,
+}
+
+/// The part of an `AuthResponse` describing the token we were issued.
+#[derive(Debug, RustcDecodable)]
+struct Auth {
+    /// The token to use as `X-Vault-Token` for subsequent requests.
+    client_token: String,
+    /// How long this token will remain valid for, in seconds.
+    lease_duration: u64,
+}
+
 /// A basic Vault client.
 struct Client {
     /// Our HTTP client.  This can be configured to mock out the network.
@@ -32,8 +64,9 @@ struct Client {
     /// Mapping from environment-variable-style names to locations in
     /// Vault.
     secretfile: Secretfile,
-    /// Local cache of secrets.
-    secrets: BTreeMap<String, Secret>,
+    /// Local cache of secrets, along with the time at which we fetched
+    /// (or last renewed) each one.
+    secrets: BTreeMap<String, (Secret, Instant)>,
 }
 
 impl Client {
@@ -62,6 +95,177 @@ impl Client {
         try!(res.read_to_string(&mut body));
         Ok(try!(json::decode(&body)))
     }
+
+    /// Do we need to (re-)fetch the secret cached at `path`?  We treat a
+    /// lease as expired a bit before Vault would, so that we never hand
+    /// out a secret that's about to become invalid.
+    fn needs_refetch(&self, path: &str) -> bool {
+        match self.secrets.get(path) {
+            None => true,
+            // A `lease_duration` of 0 means Vault isn't leasing this
+            // secret at all, so it never expires and we can cache it
+            // forever.
+            Some(&(ref secret, _)) if secret.lease_duration == 0 => false,
+            Some(&(ref secret, fetched_at)) => {
+                let margin = Duration::from_secs(secret.lease_duration * 8 / 10);
+                fetched_at.elapsed() >= margin
+            }
+        }
+    }
+
+    /// Renew the lease on the secret cached at `path`, extending its
+    /// expiry in place instead of fetching a brand-new secret.  This is
+    /// useful for long-lived callers who want to avoid losing a dynamic
+    /// credential that can't simply be re-issued.
+    pub fn renew_lease(&mut self, path: &str) -> Result<(), BoxedError> {
+        let lease_id = match self.secrets.get(path) {
+            Some(&(ref secret, _)) => secret.lease_id.clone(),
+            None => {
+                let msg = format!("No cached secret for {}, cannot renew lease", path);
+                return Err(From::from(msg));
+            }
+        };
+
+        let url = try!(self.addr.join("v1/sys/leases/renew"));
+        let body = format!("{{\"lease_id\": {}}}", json::encode(&lease_id).unwrap());
+        let req = self.client.post(url)
+            .header(XVaultToken(self.token.clone()))
+            .body(&body[..]);
+        let mut res = try!(req.send());
+
+        let mut response_body = String::new();
+        try!(res.read_to_string(&mut response_body));
+        let renewed: RenewedLease = try!(json::decode(&response_body));
+
+        if let Some(&mut (ref mut secret, ref mut fetched_at)) = self.secrets.get_mut(path) {
+            secret.lease_duration = renewed.lease_duration;
+            *fetched_at = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Log in to Vault at `login_path` with the JSON-encoded `body`, and
+    /// return the client token we were issued.
+    fn login<U>(client: &hyper::Client, addr: &U, login_path: &str, body: &str) ->
+        Result<String, BoxedError>
+        where U: hyper::client::IntoUrl + Clone
+    {
+        let url = try!(try!(addr.clone().into_url()).join(login_path));
+        let req = client.post(url).body(body);
+        let mut res = try!(req.send());
+
+        let mut response_body = String::new();
+        try!(res.read_to_string(&mut response_body));
+        let auth: AuthResponse = try!(json::decode(&response_body));
+        Ok(auth.auth.client_token)
+    }
+
+    /// Log in using the `approle` auth backend, and build a client using
+    /// the resulting token.
+    pub fn with_app_role<U>(addr: U, role_id: &str, secret_id: &str,
+                             secretfile: Secretfile) ->
+        Result<Client, BoxedError>
+        where U: hyper::client::IntoUrl + Clone
+    {
+        Client::with_app_role_and_http_client(hyper::Client::new(), addr, role_id,
+                                               secret_id, secretfile)
+    }
+
+    /// Like `with_app_role`, but takes an explicit `hyper::Client` so we
+    /// can point it at a mock connector in tests.
+    fn with_app_role_and_http_client<U>(client: hyper::Client, addr: U, role_id: &str,
+                                         secret_id: &str, secretfile: Secretfile) ->
+        Result<Client, BoxedError>
+        where U: hyper::client::IntoUrl + Clone
+    {
+        let body = format!("{{\"role_id\": {}, \"secret_id\": {}}}",
+                            json::encode(&role_id).unwrap(),
+                            json::encode(&secret_id).unwrap());
+        let token = try!(Client::login(&client, &addr, "v1/auth/approle/login", &body));
+        Client::new(client, addr, token, secretfile)
+    }
+
+    /// Log in using the legacy `app-id` auth backend, and build a client
+    /// using the resulting token.
+    pub fn with_app_id<U>(addr: U, app_id: &str, user_id: &str,
+                           secretfile: Secretfile) ->
+        Result<Client, BoxedError>
+        where U: hyper::client::IntoUrl + Clone
+    {
+        Client::with_app_id_and_http_client(hyper::Client::new(), addr, app_id,
+                                             user_id, secretfile)
+    }
+
+    /// Like `with_app_id`, but takes an explicit `hyper::Client` so we
+    /// can point it at a mock connector in tests.
+    fn with_app_id_and_http_client<U>(client: hyper::Client, addr: U, app_id: &str,
+                                       user_id: &str, secretfile: Secretfile) ->
+        Result<Client, BoxedError>
+        where U: hyper::client::IntoUrl + Clone
+    {
+        let body = format!("{{\"app_id\": {}, \"user_id\": {}}}",
+                            json::encode(&app_id).unwrap(),
+                            json::encode(&user_id).unwrap());
+        let token = try!(Client::login(&client, &addr, "v1/auth/app-id/login", &body));
+        Client::new(client, addr, token, secretfile)
+    }
+
+    /// Build a client using the standard Vault environment: `VAULT_ADDR`
+    /// for the address, and for the token, an AppRole login if
+    /// `VAULT_ROLE_ID` and `VAULT_SECRET_ID` are both set, or else
+    /// `VAULT_TOKEN` (or, failing that, `~/.vault-token`).
+    pub fn default() -> Result<Client, Error> {
+        let addr = try!(env::var("VAULT_ADDR").map_err(|_| {
+            Error::from("missing VAULT_ADDR")
+        }));
+        let secretfile = try!(Secretfile::default());
+
+        // Prefer logging in via AppRole, if we've been given role and
+        // secret IDs, over requiring a pre-issued static token.
+        if let Some((role_id, secret_id)) = Client::app_role_from_env() {
+            return Ok(try!(Client::with_app_role(addr, &role_id, &secret_id, secretfile)));
+        }
+
+        let token = try!(Client::token_from_env());
+        Ok(try!(Client::new(hyper::Client::new(), addr, token, secretfile)))
+    }
+
+    /// Look up `VAULT_ROLE_ID` and `VAULT_SECRET_ID` from the
+    /// environment, if both are set.
+    fn app_role_from_env() -> Option<(String, String)> {
+        match (env::var("VAULT_ROLE_ID"), env::var("VAULT_SECRET_ID")) {
+            (Ok(role_id), Ok(secret_id)) => Some((role_id, secret_id)),
+            _ => None,
+        }
+    }
+
+    /// Look up a Vault token using our discovery chain: the
+    /// `VAULT_TOKEN` environment variable, or failing that, the
+    /// contents of `~/.vault-token` (as written by `vault login`).
+    fn token_from_env() -> Result<String, BoxedError> {
+        if let Ok(token) = env::var("VAULT_TOKEN") {
+            return Ok(token);
+        }
+
+        let home = try!(env::var("HOME").map_err(|_| {
+            let msg = "could not find VAULT_TOKEN or HOME (for ~/.vault-token)";
+            From::<String>::from(msg.to_owned())
+        }));
+        let mut token = String::new();
+        let mut f = try!(File::open(Path::new(&home).join(".vault-token")));
+        try!(f.read_to_string(&mut token));
+        Ok(token.trim().to_owned())
+    }
+
+    /// Can we find enough configuration in our environment to build a
+    /// working Vault client, either via AppRole login or a discoverable
+    /// static token?
+    pub fn is_enabled() -> bool {
+        if env::var("VAULT_ADDR").is_err() {
+            return false;
+        }
+        Client::app_role_from_env().is_some() || Client::token_from_env().is_ok()
+    }
 }
 
 impl Backend for Client {
@@ -72,19 +276,20 @@ impl Backend for Client {
                 Err(From::from(msg))
             }
             Some(&Location::Keyed(ref path, ref key)) => {
-                // If we haven't cached this secret, do so.  This is
-                // necessary to correctly support dynamic credentials,
-                // which may have more than one related key in a single
-                // secret, and fetching the secret once per key will result
-                // in mismatched username/password pairs or whatever.
-                if !self.secrets.contains_key(path) {
+                // If we haven't cached this secret, or our lease on it is
+                // about to run out, (re-)fetch it.  This is necessary to
+                // correctly support dynamic credentials, which may have
+                // more than one related key in a single secret, and
+                // fetching the secret once per key will result in
+                // mismatched username/password pairs or whatever.
+                if self.needs_refetch(path) {
                     let secret = try!(self.get_secret(path));
-                    self.secrets.insert(path.to_owned(), secret);
+                    self.secrets.insert(path.to_owned(), (secret, Instant::now()));
                 }
 
                 // Get the secret from our cache.  `unwrap` is safe here,
                 // because if we didn't have it, we grabbed it above.
-                let secret = self.secrets.get(path).unwrap();
+                let &(ref secret, _) = self.secrets.get(path).unwrap();
 
                 // Look up the specified key in our secret's data bag.
                 secret.data.get(key).ok_or_else(|| {
@@ -100,14 +305,16 @@ mod tests {
     use backend::Backend;
     use hyper;
     use secretfile::Secretfile;
-    use super::Client;
+    use std::collections::BTreeMap;
+    use std::time::{Duration, Instant};
+    use super::{Client, Secret};
 
     mock_connector!(MockVault {
         "http://127.0.0.1" =>
           "HTTP/1.1 200 OK\r\n\
            Content-Type: application/json\r\n\
            \r\n\
-           {\"data\": {\"value\": \"bar\"},\"lease_duration\": 2592000}\r\n\
+           {\"data\": {\"value\": \"bar\"},\"lease_id\": \"secret/foo/abcd\",\"lease_duration\": 2592000}\r\n\
            "
     });
 
@@ -129,4 +336,133 @@ mod tests {
         let mut client = test_client();
         assert_eq!("bar", client.get("FOO").unwrap());
     }
+
+    fn make_secret(lease_id: &str, lease_duration: u64) -> Secret {
+        let mut data = BTreeMap::new();
+        data.insert("value".to_owned(), "bar".to_owned());
+        Secret { data: data, lease_id: lease_id.to_owned(), lease_duration: lease_duration }
+    }
+
+    #[test]
+    fn test_needs_refetch_with_no_cached_secret() {
+        let client = test_client();
+        assert!(client.needs_refetch("secret/foo"));
+    }
+
+    #[test]
+    fn test_needs_refetch_zero_lease_duration_never_expires() {
+        let mut client = test_client();
+        let long_ago = Instant::now() - Duration::from_secs(1_000_000);
+        client.secrets.insert("secret/foo".to_owned(), (make_secret("", 0), long_ago));
+        assert!(!client.needs_refetch("secret/foo"));
+    }
+
+    #[test]
+    fn test_needs_refetch_respects_lease_margin() {
+        let mut client = test_client();
+
+        // Fetched well past 80% of a 10-second lease: needs refetching.
+        let long_ago = Instant::now() - Duration::from_secs(100);
+        client.secrets.insert("secret/foo".to_owned(), (make_secret("abcd", 10), long_ago));
+        assert!(client.needs_refetch("secret/foo"));
+
+        // Just fetched, with a long lease: no need to refetch yet.
+        client.secrets.insert("secret/foo".to_owned(), (make_secret("abcd", 100), Instant::now()));
+        assert!(!client.needs_refetch("secret/foo"));
+    }
+
+    #[test]
+    fn test_get_refetches_once_lease_expires() {
+        let mut client = test_client();
+        assert_eq!("bar", client.get("FOO").unwrap());
+
+        // Force our cached secret to look like its lease is almost up.
+        {
+            let cached = client.secrets.get_mut("secret/foo").unwrap();
+            cached.0.lease_duration = 10;
+            cached.1 = Instant::now() - Duration::from_secs(100);
+        }
+        assert!(client.needs_refetch("secret/foo"));
+
+        // Fetching again should succeed by going back to Vault.
+        assert_eq!("bar", client.get("FOO").unwrap());
+        assert!(!client.needs_refetch("secret/foo"));
+    }
+
+    mock_connector!(MockVaultRenew {
+        "http://127.0.0.1" =>
+          "HTTP/1.1 200 OK\r\n\
+           Content-Type: application/json\r\n\
+           \r\n\
+           {\"lease_id\": \"secret/foo/abcd\", \"lease_duration\": 7200}\r\n\
+           "
+    });
+
+    #[test]
+    fn test_renew_lease() {
+        let h = hyper::Client::with_connector(MockVaultRenew::default());
+        let secretfile = Secretfile::from_str("FOO secret/foo:value").unwrap();
+        let mut client = Client::new(h, "http://127.0.0.1", "123", secretfile).unwrap();
+
+        let long_ago = Instant::now() - Duration::from_secs(100);
+        client.secrets.insert("secret/foo".to_owned(), (make_secret("secret/foo/abcd", 10), long_ago));
+        assert!(client.needs_refetch("secret/foo"));
+
+        client.renew_lease("secret/foo").unwrap();
+
+        assert!(!client.needs_refetch("secret/foo"));
+        let &(ref secret, _) = client.secrets.get("secret/foo").unwrap();
+        assert_eq!(7200, secret.lease_duration);
+    }
+
+    #[test]
+    fn test_renew_lease_without_cached_secret() {
+        let mut client = test_client();
+        assert!(client.renew_lease("secret/foo").is_err());
+    }
+
+    mock_connector!(MockVaultAppRoleLogin {
+        "http://127.0.0.1" =>
+          "HTTP/1.1 200 OK\r\n\
+           Content-Type: application/json\r\n\
+           \r\n\
+           {\"auth\": {\"client_token\": \"s.approletoken\", \"lease_duration\": 3600}}\r\n\
+           "
+    });
+
+    mock_connector!(MockVaultAppRoleLoginFailure {
+        "http://127.0.0.1" =>
+          "HTTP/1.1 403 Forbidden\r\n\
+           Content-Type: application/json\r\n\
+           \r\n\
+           {\"errors\": [\"invalid role or secret ID\"]}\r\n\
+           "
+    });
+
+    #[test]
+    fn test_with_app_role() {
+        let h = hyper::Client::with_connector(MockVaultAppRoleLogin::default());
+        let secretfile = Secretfile::from_str("FOO secret/foo:value").unwrap();
+        let client = Client::with_app_role_and_http_client(
+            h, "http://127.0.0.1", "some-role", "some-secret", secretfile).unwrap();
+        assert_eq!("s.approletoken", client.token);
+    }
+
+    #[test]
+    fn test_with_app_role_login_failure() {
+        let h = hyper::Client::with_connector(MockVaultAppRoleLoginFailure::default());
+        let secretfile = Secretfile::from_str("FOO secret/foo:value").unwrap();
+        let result = Client::with_app_role_and_http_client(
+            h, "http://127.0.0.1", "bad-role", "bad-secret", secretfile);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_with_app_id() {
+        let h = hyper::Client::with_connector(MockVaultAppRoleLogin::default());
+        let secretfile = Secretfile::from_str("FOO secret/foo:value").unwrap();
+        let client = Client::with_app_id_and_http_client(
+            h, "http://127.0.0.1", "some-app", "some-user", secretfile).unwrap();
+        assert_eq!("s.approletoken", client.token);
+    }
 }